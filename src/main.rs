@@ -1,31 +1,66 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use pbkdf2::pbkdf2_hmac;
+use rand::{rngs::OsRng, RngCore};
 use reqwest::{blocking::multipart, header::AUTHORIZATION};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::{
+    collections::HashMap,
     env, fs,
-    io::{stdin, Read, Write},
+    io::{stdin, IsTerminal, Read, Write},
     path::PathBuf,
-    process::Command,
+    process::{exit, Command},
 };
 use zip::write::SimpleFileOptions;
 
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// The current `Config` schema version. Bump this and append a migration
+/// function whenever a field is added, renamed, or restructured.
+const CONFIG_VERSION: i64 = 2;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Config {
+    #[serde(default)]
+    version: i64,
     build_command: Option<String>,
     directory: Option<String>,
     domain: Option<String>,
     auth: Option<String>,
+    enc_salt: Option<String>,
+    enc_nonce: Option<String>,
+    enc_auth: Option<String>,
+    ignore: Option<Vec<String>>,
+    preview: Option<bool>,
 }
 
 impl Config {
     pub fn default() -> Config {
         Config {
+            // start at version 0 so `migrate_config` runs the same migrations
+            // (default ignore patterns, explicit preview opt-out, ...) for a
+            // brand-new project as it would for an old config being upgraded
+            version: 0,
             build_command: Some("npm run build".into()),
             directory: Some("build".into()),
             domain: None,
             auth: None,
+            enc_salt: None,
+            enc_nonce: None,
+            enc_auth: None,
+            ignore: None,
+            preview: None,
         }
     }
 
+    fn is_encrypted(&self) -> bool {
+        self.enc_salt.is_some() && self.enc_nonce.is_some() && self.enc_auth.is_some()
+    }
+
     fn to_string(&self) -> String {
         let not_set = String::from("not set");
         return format!(
@@ -37,35 +72,225 @@ impl Config {
     }
 }
 
+/// The on-disk dialect a config was read from (and will be written back as).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// Finds the first config file present in `base_path`, preferring the
+/// explicit `.uploader.yaml`/`.uploader.toml` extensions and falling back to
+/// probing the bare `.uploader` file against all three parsers.
+fn locate_config_file(base_path: &PathBuf) -> Option<(PathBuf, ConfigFormat)> {
+    let yaml_path = base_path.join(".uploader.yaml");
+    if yaml_path.exists() {
+        return Some((yaml_path, ConfigFormat::Yaml));
+    }
+
+    let toml_path = base_path.join(".uploader.toml");
+    if toml_path.exists() {
+        return Some((toml_path, ConfigFormat::Toml));
+    }
+
+    let json_path = base_path.join(".uploader");
+    if json_path.exists() {
+        let contents =
+            fs::read_to_string(&json_path).expect("SHOULD HAVE BEEN ABLE TO READ THE FILE");
+        let format = detect_config_format(&contents).expect("COULD NOT DETECT CONFIG FORMAT");
+        return Some((json_path, format));
+    }
+
+    None
+}
+
+fn detect_config_format(contents: &str) -> Option<ConfigFormat> {
+    if serde_json::from_str::<Config>(contents).is_ok() {
+        return Some(ConfigFormat::Json);
+    }
+    if toml::from_str::<Config>(contents).is_ok() {
+        return Some(ConfigFormat::Toml);
+    }
+    if serde_yaml::from_str::<Config>(contents).is_ok() {
+        return Some(ConfigFormat::Yaml);
+    }
+    None
+}
+
+fn deserialize_config(contents: &str, format: ConfigFormat) -> Config {
+    match format {
+        ConfigFormat::Json => {
+            serde_json::from_str(contents).expect("FAILED TO DESERIALIZE CONFIG FILE")
+        }
+        ConfigFormat::Yaml => {
+            serde_yaml::from_str(contents).expect("FAILED TO DESERIALIZE CONFIG FILE")
+        }
+        ConfigFormat::Toml => toml::from_str(contents).expect("FAILED TO DESERIALIZE CONFIG FILE"),
+    }
+}
+
+fn serialize_config(config: &Config, format: ConfigFormat) -> String {
+    match format {
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(config).expect("CONFIG COULD NOT BE SERIALIZED")
+        }
+        ConfigFormat::Yaml => {
+            serde_yaml::to_string(config).expect("CONFIG COULD NOT BE SERIALIZED")
+        }
+        ConfigFormat::Toml => {
+            toml::to_string_pretty(config).expect("CONFIG COULD NOT BE SERIALIZED")
+        }
+    }
+}
+
+/// Ordered schema migrations, one per version bump. `migrations()[v]` upgrades
+/// a config from version `v` to `v + 1`.
+fn migrations() -> Vec<fn(&mut Config)> {
+    vec![
+        // v0 -> v1: new projects now ship with sane default ignore patterns
+        // so secrets don't accidentally end up in the first upload.
+        |config| {
+            if config.ignore.is_none() {
+                config.ignore = Some(vec![".env".into(), "*.map".into()]);
+            }
+        },
+        // v1 -> v2: preview mode became an explicit, persisted opt-in.
+        |config| {
+            if config.preview.is_none() {
+                config.preview = Some(false);
+            }
+        },
+    ]
+}
+
+/// Runs any pending migrations against `config`, bumping `version` one step
+/// at a time until it reaches `CONFIG_VERSION`. Returns whether anything changed.
+fn migrate_config(config: &mut Config) -> bool {
+    let starting_version = config.version;
+    let steps = migrations();
+
+    if config.version < 0 {
+        config.version = 0;
+    }
+
+    while config.version < CONFIG_VERSION {
+        steps[config.version as usize](config);
+        config.version += 1;
+    }
+
+    config.version != starting_version
+}
+
+/// Reads an env var and treats an empty string the same as unset.
+fn env_override(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|v| !v.is_empty())
+}
+
+/// `~/.config/project-uploader/credentials` (or the platform equivalent),
+/// holding auth tokens keyed by domain so they don't need to live in any
+/// project's `.uploader` file.
+fn credentials_path() -> PathBuf {
+    let dirs = directories::ProjectDirs::from("", "", "project-uploader")
+        .expect("COULD NOT DETERMINE APP DATA DIRECTORY");
+    dirs.config_dir().join("credentials")
+}
+
+fn load_credentials() -> HashMap<String, String> {
+    let path = credentials_path();
+
+    if !path.exists() {
+        return HashMap::new();
+    }
+
+    let contents = fs::read_to_string(&path).expect("COULD NOT READ CREDENTIAL STORE");
+    serde_json::from_str(&contents).expect("COULD NOT DESERIALIZE CREDENTIAL STORE")
+}
+
+fn save_credentials(credentials: &HashMap<String, String>) {
+    let path = credentials_path();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("COULD NOT CREATE APP DATA DIRECTORY");
+    }
+
+    let serialized =
+        serde_json::to_string_pretty(credentials).expect("CREDENTIAL STORE COULD NOT BE SERIALIZED");
+    fs::write(path, serialized).expect("CREDENTIAL STORE COULD NOT BE WRITTEN");
+}
+
+fn fail_missing(field: &str) -> ! {
+    eprintln!(
+        "MISSING REQUIRED CONFIG FIELD: {} (set it via env var, config file, or run without --non-interactive in a terminal)",
+        field
+    );
+    exit(1);
+}
+
 fn main() -> () {
     let path = env::current_dir().expect("CURRENT PATH NOT SET");
-    let config_path = path.join(".uploader");
+    let non_interactive = env::args().any(|arg| arg == "--non-interactive");
 
     let mut config = Config::default();
 
-    let is_default = match config_path.exists() {
-        true => {
+    let (config_path, format, is_default) = match locate_config_file(&path) {
+        Some((found_path, format)) => {
             let contents =
-                fs::read_to_string(&config_path).expect("SHOULD HAVE BEEN ABLE TO READ THE FILE");
+                fs::read_to_string(&found_path).expect("SHOULD HAVE BEEN ABLE TO READ THE FILE");
 
-            config = serde_json::from_str(&contents).expect("FAILED TO DESERIALIZE CONFIG FILE");
+            config = deserialize_config(&contents, format);
             println!("\nCONFIG:\n{}", config.to_string());
-            false
+            (found_path, format, false)
         }
-        false => true,
+        None => (path.join(".uploader"), ConfigFormat::Json, true),
     };
 
-    if config.directory.is_none() || is_default {
+    // seeds defaults for a brand-new config too, but there's nothing on disk
+    // yet to rewrite or report a migration for until after prompting below
+    if migrate_config(&mut config) && !is_default {
+        println!("MIGRATED CONFIG TO VERSION {}", config.version);
+        let serialized = serialize_config(&config, format);
+        fs::write(&config_path, serialized).expect("CONFIG COULD NOT BE WRITTEN");
+    }
+
+    // a plaintext auth already sitting in the file predates the credential
+    // store and should be migrated into it rather than just discarded later
+    let file_auth = config.auth.clone();
+
+    // the env layer takes precedence over whatever was loaded from the file
+    if let Some(domain) = env_override("UPLOADER_DOMAIN") {
+        config.domain = Some(domain);
+    }
+    let auth_env_override = env_override("UPLOADER_AUTH");
+    if let Some(auth) = &auth_env_override {
+        config.auth = Some(auth.clone());
+    }
+    if let Some(directory) = env_override("UPLOADER_DIRECTORY") {
+        config.directory = Some(directory);
+    }
+    if let Some(build_command) = env_override("UPLOADER_BUILD_COMMAND") {
+        config.build_command = Some(build_command);
+    }
+
+    let can_prompt = !non_interactive && stdin().is_terminal();
+
+    if can_prompt && (config.directory.is_none() || is_default) {
         config.directory = read_from_stdin(String::from("SET THE DIRECTORY:"), config.directory);
+    } else if config.directory.is_none() {
+        fail_missing("directory");
     }
 
-    if config.build_command.is_none() || is_default {
+    if can_prompt && (config.build_command.is_none() || is_default) {
         config.build_command =
             read_from_stdin(String::from("SET THE BUILD COMMAND:"), config.build_command);
+    } else if config.build_command.is_none() {
+        fail_missing("build_command");
     }
 
-    if config.domain.is_none() || is_default {
+    if can_prompt && (config.domain.is_none() || is_default) {
         config.domain = read_from_stdin(String::from("SET THE DOMAIN"), config.domain);
+    } else if config.domain.is_none() {
+        fail_missing("domain");
     }
 
     if let Some(domain) = &config.domain {
@@ -74,8 +299,93 @@ fn main() -> () {
         }
     }
 
-    if config.auth.is_none() || is_default {
-        config.auth = read_from_stdin(String::from("AUTHENTICATION KEY"), config.auth);
+    // the domain the credential store is keyed on, captured before any
+    // per-branch preview subdomain is derived below
+    let credential_domain = config.domain.clone().expect("DOMAIN NOT SET");
+
+    let git = git_info(&path);
+
+    if let Some(git) = &git {
+        if git.dirty {
+            println!("WARNING: WORKING TREE HAS UNCOMMITTED CHANGES");
+        }
+
+        if config.preview.unwrap_or(false) && git.branch != "main" && git.branch != "master" {
+            if let Some(domain) = &config.domain {
+                config.domain = Some(preview_domain(domain, &git.branch));
+                println!(
+                    "PREVIEW DEPLOY FOR BRANCH '{}': {}",
+                    git.branch,
+                    config.domain.as_ref().unwrap()
+                );
+            }
+        }
+    }
+
+    if config.auth.is_some() {
+        // an explicit override (UPLOADER_AUTH or a hand-edited local plaintext
+        // config) wins over both the encrypted fields and the credential store
+        if auth_env_override.is_none() && file_auth.is_some() {
+            // this plaintext token predates the credential store - migrate it
+            // in rather than silently dropping it on the next write
+            let mut credentials = load_credentials();
+            if !credentials.contains_key(&credential_domain) {
+                credentials.insert(credential_domain.clone(), file_auth.clone().unwrap());
+                save_credentials(&credentials);
+                println!(
+                    "MIGRATED AUTH KEY FOR {} INTO THE CREDENTIAL STORE",
+                    credential_domain
+                );
+            }
+        }
+    } else if config.is_encrypted() {
+        if !can_prompt {
+            fail_missing("auth (passphrase required to decrypt an encrypted auth key)");
+        }
+
+        let passphrase = read_from_stdin(String::from("PASSPHRASE TO DECRYPT AUTH KEY"), None)
+            .expect("PASSPHRASE REQUIRED TO DECRYPT AUTH KEY");
+
+        config.auth = Some(decrypt_auth(
+            config.enc_salt.as_ref().unwrap(),
+            config.enc_nonce.as_ref().unwrap(),
+            config.enc_auth.as_ref().unwrap(),
+            &passphrase,
+        ));
+    } else {
+        let mut credentials = load_credentials();
+
+        if let Some(token) = credentials.get(&credential_domain) {
+            config.auth = Some(token.clone());
+        } else if can_prompt {
+            let auth = read_from_stdin(String::from("AUTHENTICATION KEY"), None)
+                .expect("AUTH NOT SET");
+
+            credentials.insert(credential_domain.clone(), auth.clone());
+            save_credentials(&credentials);
+            println!("SAVED AUTH KEY FOR {} TO THE CREDENTIAL STORE", credential_domain);
+
+            config.auth = Some(auth);
+
+            let encrypt = read_from_stdin(
+                String::from("ALSO PIN AN ENCRYPTED AUTH KEY TO THIS PROJECT? (y/n)"),
+                Some(String::from("n")),
+            );
+
+            if encrypt.as_deref() == Some("y") {
+                let passphrase = read_from_stdin(String::from("SET A PASSPHRASE"), None)
+                    .expect("PASSPHRASE REQUIRED TO ENCRYPT AUTH KEY");
+
+                let (salt, nonce, ciphertext) =
+                    encrypt_auth(config.auth.as_ref().expect("AUTH NOT SET"), &passphrase);
+
+                config.enc_salt = Some(salt);
+                config.enc_nonce = Some(nonce);
+                config.enc_auth = Some(ciphertext);
+            }
+        } else {
+            fail_missing("auth");
+        }
     }
 
     let result = run_build(&config);
@@ -87,11 +397,18 @@ fn main() -> () {
 
     let zip = zip_output(&path, &config);
 
-    upload_zip(zip, &config);
+    upload_zip(zip, &config, git.as_ref());
 
-    // write config file
-    let serialized = serde_json::to_string_pretty(&config).expect("CONFIG COULD NOT BE SERIALIZED");
-    fs::write(config_path, serialized).expect("CONFIG COULD NOT BE WRITTEN");
+    // write config file - the auth secret lives in the global credential
+    // store (or, if pinned, the encrypted fields above), never in plaintext here
+    config.auth = None;
+    let serialized = serialize_config(&config, format);
+    let config_file_name = config_path
+        .file_name()
+        .expect("CONFIG PATH HAS NO FILE NAME")
+        .to_string_lossy()
+        .to_string();
+    fs::write(&config_path, serialized).expect("CONFIG COULD NOT BE WRITTEN");
 
     // if its a git repo, add the config file to the .gitignore
     let gitignore = path.join(".gitignore");
@@ -100,14 +417,62 @@ fn main() -> () {
         let contents = fs::read_to_string(&gitignore).expect("GITIGNORE COULD NOT BE READ");
         let mut lines = contents.lines().collect::<Vec<&str>>();
 
-        if !lines.contains(&".uploader") {
-            lines.push(".uploader");
+        if !lines.contains(&config_file_name.as_str()) {
+            lines.push(&config_file_name);
             let new_contents = lines.join("\n");
             fs::write(gitignore, new_contents).expect("GITIGNORE COULD NOT BE WRITTEN");
         }
     }
 }
 
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key);
+    key
+}
+
+/// Encrypts `auth` with a key derived from `passphrase`, returning
+/// `(salt_b64, nonce_b64, ciphertext_b64)` for storage in the config.
+fn encrypt_auth(auth: &str, passphrase: &str) -> (String, String, String) {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut nonce_bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    let key = derive_key(passphrase, &salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, auth.as_bytes())
+        .expect("FAILED TO ENCRYPT AUTH KEY");
+
+    (
+        BASE64.encode(salt),
+        BASE64.encode(nonce_bytes),
+        BASE64.encode(ciphertext),
+    )
+}
+
+fn decrypt_auth(salt_b64: &str, nonce_b64: &str, ciphertext_b64: &str, passphrase: &str) -> String {
+    let salt = BASE64.decode(salt_b64).expect("INVALID SALT IN CONFIG");
+    let nonce_bytes = BASE64.decode(nonce_b64).expect("INVALID NONCE IN CONFIG");
+    let ciphertext = BASE64
+        .decode(ciphertext_b64)
+        .expect("INVALID CIPHERTEXT IN CONFIG");
+
+    let key = derive_key(passphrase, &salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let plaintext = cipher
+        .decrypt(nonce, ciphertext.as_ref())
+        .expect("FAILED TO DECRYPT AUTH KEY, WRONG PASSPHRASE?");
+
+    String::from_utf8(plaintext).expect("DECRYPTED AUTH KEY IS NOT VALID UTF-8")
+}
+
 fn read_from_stdin(query: String, default: Option<String>) -> Option<String> {
     let mut buffer = String::new();
 
@@ -169,6 +534,29 @@ fn run_build(config: &Config) -> Result<(), ()> {
     return Ok(());
 }
 
+/// Builds a gitignore-style matcher from `config.ignore` plus an optional
+/// `.uploaderignore` file in `base_path`, so secrets never end up in the zip.
+fn build_ignore_matcher(base_path: &PathBuf, config: &Config) -> ignore::gitignore::Gitignore {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(base_path);
+
+    if let Some(patterns) = &config.ignore {
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .expect("INVALID IGNORE PATTERN IN CONFIG");
+        }
+    }
+
+    let uploaderignore = base_path.join(".uploaderignore");
+    if uploaderignore.exists() {
+        if let Some(err) = builder.add(&uploaderignore) {
+            println!("ERROR READING .uploaderignore: {}", err);
+        }
+    }
+
+    builder.build().expect("COULD NOT BUILD IGNORE MATCHER")
+}
+
 fn zip_output<'a>(base_path: &PathBuf, config: &Config) -> PathBuf {
     let dir = config.directory.to_owned().expect("output not set");
     let path = base_path.join(&dir);
@@ -180,6 +568,9 @@ fn zip_output<'a>(base_path: &PathBuf, config: &Config) -> PathBuf {
         panic!("OUTPUT DIRECTORY DOES NOT EXIST");
     }
 
+    let ignore_matcher = build_ignore_matcher(base_path, config);
+    let mut excluded_count = 0;
+
     // zip the output directory
     let output_path = base_path.join("output.zip");
 
@@ -205,6 +596,14 @@ fn zip_output<'a>(base_path: &PathBuf, config: &Config) -> PathBuf {
                 name = name.replacen(&dir_with_slash, "", 1);
             }
 
+            if ignore_matcher
+                .matched_path_or_any_parents(&name, false)
+                .is_ignore()
+            {
+                excluded_count += 1;
+                continue;
+            }
+
             zip.start_file(&name, SimpleFileOptions::default())
                 .expect("COULD NOT START FILE");
 
@@ -219,11 +618,65 @@ fn zip_output<'a>(base_path: &PathBuf, config: &Config) -> PathBuf {
 
     zip.finish().expect("COULD NOT FINISH ZIP");
     println!("ZIP CREATED: {}", output_path.to_string_lossy());
+    println!("EXCLUDED {} FILE(S) VIA IGNORE PATTERNS", excluded_count);
 
     output_path
 }
 
-fn upload_zip(zip: PathBuf, config: &Config) {
+#[derive(Debug, Clone)]
+struct GitInfo {
+    branch: String,
+    commit: String,
+    dirty: bool,
+}
+
+/// Reads the current branch, short commit SHA, and dirty state via `git`,
+/// returning `None` when `base_path` isn't inside a git repository.
+fn git_info(base_path: &PathBuf) -> Option<GitInfo> {
+    let branch = run_git(base_path, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let commit = run_git(base_path, &["rev-parse", "--short", "HEAD"])?;
+    let status = run_git(base_path, &["status", "--porcelain"])?;
+
+    Some(GitInfo {
+        branch,
+        commit,
+        dirty: !status.is_empty(),
+    })
+}
+
+fn run_git(base_path: &PathBuf, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(base_path)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn slugify_branch(branch: &str) -> String {
+    branch
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// Derives a per-branch preview domain, e.g. `feature-x.myapp.example.com`.
+fn preview_domain(domain: &str, branch: &str) -> String {
+    let slug = slugify_branch(branch);
+
+    match domain.split_once("://") {
+        Some((scheme, host)) => format!("{}://{}.{}", scheme, slug, host),
+        None => format!("{}.{}", slug, domain),
+    }
+}
+
+fn upload_zip(zip: PathBuf, config: &Config, git: Option<&GitInfo>) {
     let domain = &config.domain.to_owned().expect("DOMAIN NOT SET");
     let form = multipart::Form::new()
         .file("zip", &zip)
@@ -232,11 +685,18 @@ fn upload_zip(zip: PathBuf, config: &Config) {
     let auth = config.auth.to_owned().expect("AUTH NOT SET");
 
     let client = reqwest::blocking::Client::new();
-    let resp = client
+    let mut request = client
         .post(domain)
         .header(AUTHORIZATION, auth)
-        .multipart(form)
-        .send();
+        .multipart(form);
+
+    if let Some(git) = git {
+        request = request
+            .header("X-Deploy-Branch", git.branch.clone())
+            .header("X-Deploy-Commit", git.commit.clone());
+    }
+
+    let resp = request.send();
 
     match resp {
         Ok(response) => {